@@ -1,12 +1,18 @@
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, DirEntry};
+use std::io::{Read, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 use std::time::Instant;
 
 use anyhow::{anyhow, Result};
 use image::GenericImageView;
 use iter_tools::Itertools;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
+use siphasher::sip128::{Hash128, Hasher128, SipHasher13};
 
 #[derive(Debug)]
 struct ObjectDetection {
@@ -16,12 +22,37 @@ struct ObjectDetection {
     height: u32,
     // Directory name containing image
     class: String,
+    size: u64,
+    partial_hash: Hash128,
 }
 
+/// How deep to hash a file when looking for duplicates.
+///
+/// `Partial` only reads the first block, which is enough to bucket
+/// candidates cheaply; `Full` reads the whole file and is only needed to
+/// confirm a match within a bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HashMode {
+    Partial,
+    Full,
+}
+
+const PARTIAL_HASH_BYTES: usize = 4096;
+
+#[derive(Debug)]
+struct DroppedDuplicate {
+    // The file that was kept
+    canonical: PathBuf,
+    // The file that was dropped in its favor
+    duplicate: PathBuf,
+}
+
+/// Maps arbitrary, TOML-declared group names (e.g. `tanks`, `lavs`) to their
+/// [`LabelGroup`], so new classes can be added without code changes.
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(transparent)]
 struct LabelClassification {
-    tanks: LabelGroup,
-    lavs: LabelGroup,
+    groups: HashMap<String, LabelGroup>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -30,23 +61,67 @@ struct LabelGroup {
     labels: Vec<String>,
 }
 
+/// Directory and file path patterns skipped during traversal, so scratch or
+/// generated output folders aren't scanned.
+const DEFAULT_IGNORE_PATTERNS: &[&str] =
+    &["__pycache__", ".git", "tmp_*", "augmented", "*/augmented/*"];
+
+#[derive(Debug, Default)]
+struct TraversalSummary {
+    processed: usize,
+    skipped_dirs: usize,
+    skipped_files: usize,
+    errors: Vec<(PathBuf, anyhow::Error)>,
+}
+
 fn main() -> Result<()> {
     // Get the path to the image file from the command-line arguments
     let args: Vec<String> = std::env::args().collect();
     let dir = args.get(1).map_or("images", |s| s);
+    let ignore_patterns = parse_ignore_patterns(&args)?;
+    let strict = args.iter().any(|arg| arg == "--strict");
+    let (split_ratios, split_seed) = parse_split_config(&args)?;
 
     let toml_str = include_str!("../label_classification2.toml");
     let classifier: LabelClassification = toml::from_str(toml_str)?;
+    classifier.validate()?;
     println!("{:#?}", classifier);
 
     // Measure the execution time of the directory traversal
     let start_time = Instant::now();
-    let result = traverse_images(dir)?;
+    let (result, summary) = traverse_images(dir, &ignore_patterns)?;
     let elapsed_time = start_time.elapsed();
+    println!(
+        "Traversal summary: {} processed, {} skipped ({} dirs, {} files), {} failed",
+        summary.processed,
+        summary.skipped_dirs + summary.skipped_files,
+        summary.skipped_dirs,
+        summary.skipped_files,
+        summary.errors.len()
+    );
+    for (path, error) in &summary.errors {
+        println!("  failed: {} ({error})", path.display());
+    }
+    if strict && !summary.errors.is_empty() {
+        return Err(anyhow!(
+            "{} file(s) failed to process in --strict mode",
+            summary.errors.len()
+        ));
+    }
+
+    let (result, dropped_duplicates) = dedup_detections(result, HashMode::Full)?;
+    println!("Dropped {} duplicate image(s):", dropped_duplicates.len());
+    for duplicate in &dropped_duplicates {
+        println!(
+            "  {} -> kept {}",
+            duplicate.duplicate.display(),
+            duplicate.canonical.display()
+        );
+    }
 
     // Export the result to a CSV file
     let export_start_time = Instant::now();
-    export(&result, &classifier)?;
+    export(&result, &classifier, split_ratios, split_seed)?;
     let export_elapsed_time = export_start_time.elapsed();
 
     print_unique_classes(&result);
@@ -66,45 +141,238 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn traverse_images<P: AsRef<Path>>(root: P) -> Result<Vec<ObjectDetection>> {
-    // Get a list of all directories in the root directory
+fn traverse_images<P: AsRef<Path>>(
+    root: P,
+    ignore_patterns: &[glob::Pattern],
+) -> Result<(Vec<ObjectDetection>, TraversalSummary)> {
+    let root = root.as_ref();
+
+    // Get a list of all directories in the root directory, skipping excluded
+    // ones before we ever read their contents
+    let mut skipped_dirs = 0;
+    let mut entry_errors = Vec::new();
     let dirs: Vec<_> = fs::read_dir(root)?
-        .filter_map(Result::ok)
+        .filter_map(|entry| match entry {
+            Ok(entry) => Some(entry),
+            Err(error) => {
+                entry_errors.push((root.to_path_buf(), anyhow::Error::new(error)));
+                None
+            }
+        })
         .filter(|entry| entry.path().is_dir())
+        .filter(|entry| {
+            if is_excluded(&entry.path(), ignore_patterns) {
+                skipped_dirs += 1;
+                false
+            } else {
+                true
+            }
+        })
         .collect();
 
     // Parallelize the processing of directories using rayon
-    let detections: Vec<Vec<_>> = dirs
+    let results: Vec<(Vec<_>, usize, Vec<(PathBuf, anyhow::Error)>)> = dirs
         .into_par_iter()
         .map(|entry| {
-            // Get a list of all image files in the directory
-            let files = fs::read_dir(entry.path())?
-                .filter_map(Result::ok)
-                .filter(|entry| entry.path().is_file() && is_supported_image(entry.path()));
-
-            // Process the image files in the directory
-            let detections = files
-                .map(|entry| {
-                    let path = entry.path();
+            // Get a list of all image files in the directory, skipping excluded ones
+            let mut skipped_files = 0;
+            let mut errors = Vec::new();
+            let dir_path = entry.path();
+            let dir_entries = match fs::read_dir(&dir_path) {
+                Ok(dir_entries) => dir_entries,
+                // An unreadable class directory (e.g. permission denied) is a
+                // per-directory failure, not a reason to abort the whole run.
+                Err(error) => return Ok((Vec::new(), 0, vec![(dir_path, error.into())])),
+            };
+            let files: Vec<_> = dir_entries
+                .filter_map(|file_entry| match file_entry {
+                    Ok(file_entry) => Some(file_entry),
+                    Err(error) => {
+                        errors.push((dir_path.clone(), anyhow::Error::new(error)));
+                        None
+                    }
+                })
+                .filter(|entry| entry.path().is_file() && is_supported_image(entry.path()))
+                .filter(|entry| {
+                    if is_excluded(&entry.path(), ignore_patterns) {
+                        skipped_files += 1;
+                        false
+                    } else {
+                        true
+                    }
+                })
+                .collect();
+
+            // Process the image files in the directory, collecting failures
+            // instead of aborting the whole run on the first one
+            let mut detections = Vec::new();
+            for entry in files {
+                let path = entry.path();
+                let detection = (|| -> Result<ObjectDetection> {
                     let class = extract_class(&entry).ok_or_else(|| anyhow!("Class not found"))?;
                     let (width, height) = image_size(&path)?;
+                    let size = fs::metadata(&path)?.len();
+                    let partial_hash = hash_file(&path, HashMode::Partial)?;
                     Ok(ObjectDetection {
-                        filename: path,
+                        filename: path.clone(),
                         width,
                         height,
                         class,
+                        size,
+                        partial_hash,
                     })
-                })
-                .collect::<Result<Vec<_>>>()?;
+                })();
 
-            Ok(detections)
+                match detection {
+                    Ok(detection) => detections.push(detection),
+                    Err(error) => errors.push((path, error)),
+                }
+            }
+
+            Ok((detections, skipped_files, errors))
         })
         .collect::<Result<_>>()?;
 
-    // Flatten the nested vector of detections into a single vector
-    let detections = detections.into_iter().flatten().collect::<Vec<_>>();
+    // Flatten the nested vectors of detections and errors into single vectors
+    let mut skipped_files = 0;
+    let mut errors = entry_errors;
+    let detections = results
+        .into_iter()
+        .flat_map(|(detections, skipped, dir_errors)| {
+            skipped_files += skipped;
+            errors.extend(dir_errors);
+            detections
+        })
+        .collect::<Vec<_>>();
+
+    let summary = TraversalSummary {
+        processed: detections.len(),
+        skipped_dirs,
+        skipped_files,
+        errors,
+    };
 
-    Ok(detections)
+    Ok((detections, summary))
+}
+
+/// Parses `--exclude <pattern>` flags (repeatable) from the command line and
+/// combines them with [`DEFAULT_IGNORE_PATTERNS`].
+fn parse_ignore_patterns(args: &[String]) -> Result<Vec<glob::Pattern>> {
+    let mut patterns: Vec<String> = DEFAULT_IGNORE_PATTERNS
+        .iter()
+        .map(|pattern| pattern.to_string())
+        .collect();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--exclude" {
+            let pattern = args
+                .next()
+                .ok_or_else(|| anyhow!("--exclude requires a pattern argument"))?;
+            patterns.push(pattern.to_owned());
+        }
+    }
+
+    patterns
+        .iter()
+        .map(|pattern| glob::Pattern::new(pattern).map_err(Into::into))
+        .collect()
+}
+
+/// Matches a path's full string form and its final component against every
+/// ignore pattern, so both `tmp_*`-style name patterns and `*/augmented/*`-style
+/// path patterns work as expected.
+fn is_excluded(path: &Path, ignore_patterns: &[glob::Pattern]) -> bool {
+    let path_str = path.to_string_lossy();
+    let name_str = path.file_name().map(|name| name.to_string_lossy());
+
+    ignore_patterns.iter().any(|pattern| {
+        pattern.matches(&path_str)
+            || name_str
+                .as_deref()
+                .is_some_and(|name| pattern.matches(name))
+    })
+}
+
+fn hash_file<P: AsRef<Path>>(path: P, mode: HashMode) -> Result<Hash128> {
+    let mut file = fs::File::open(path.as_ref())?;
+    let mut hasher = SipHasher13::new();
+    match mode {
+        HashMode::Partial => {
+            // `read_to_end` loops internally until EOF, unlike a single `read`
+            // call, so `buf` always holds the full first block (or less, at EOF).
+            let mut buf = Vec::with_capacity(PARTIAL_HASH_BYTES);
+            file.take(PARTIAL_HASH_BYTES as u64).read_to_end(&mut buf)?;
+            hasher.write(&buf);
+        }
+        HashMode::Full => {
+            let mut buf = Vec::new();
+            file.read_to_end(&mut buf)?;
+            hasher.write(&buf);
+        }
+    }
+    Ok(hasher.finish128())
+}
+
+/// Collapses byte-identical images to a single row, keeping the first-seen
+/// path as canonical. Candidates are first bucketed by `(size, partial_hash)`;
+/// `HashMode::Full` additionally confirms matches with a whole-file hash
+/// before a bucket is treated as duplicates.
+fn dedup_detections(
+    data: Vec<ObjectDetection>,
+    mode: HashMode,
+) -> Result<(Vec<ObjectDetection>, Vec<DroppedDuplicate>)> {
+    let mut buckets: HashMap<(u64, Hash128), Vec<usize>> = HashMap::new();
+    for (i, detection) in data.iter().enumerate() {
+        buckets
+            .entry((detection.size, detection.partial_hash))
+            .or_default()
+            .push(i);
+    }
+
+    let mut dropped = Vec::new();
+    let mut drop_indices = HashSet::new();
+
+    for indices in buckets.into_values() {
+        if indices.len() < 2 {
+            continue;
+        }
+
+        let groups: Vec<Vec<usize>> = match mode {
+            HashMode::Partial => vec![indices],
+            HashMode::Full => {
+                let mut by_full_hash: HashMap<Hash128, Vec<usize>> = HashMap::new();
+                for i in indices {
+                    let full_hash = hash_file(&data[i].filename, HashMode::Full)?;
+                    by_full_hash.entry(full_hash).or_default().push(i);
+                }
+                by_full_hash.into_values().collect()
+            }
+        };
+
+        for group in groups {
+            if group.len() < 2 {
+                continue;
+            }
+            let canonical = group[0];
+            for &duplicate in &group[1..] {
+                drop_indices.insert(duplicate);
+                dropped.push(DroppedDuplicate {
+                    canonical: data[canonical].filename.clone(),
+                    duplicate: data[duplicate].filename.clone(),
+                });
+            }
+        }
+    }
+
+    let kept = data
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !drop_indices.contains(i))
+        .map(|(_, detection)| detection)
+        .collect();
+
+    Ok((kept, dropped))
 }
 
 fn extract_class(entry: &DirEntry) -> Option<String> {
@@ -154,50 +422,216 @@ fn print_unique_classes(data: &[ObjectDetection]) {
 
 impl LabelClassification {
     fn get_class(&self, label: &str) -> Option<&str> {
-        // TODO: fix harcoding of [&self.tanks, &self.lavs] fields
-        for group in [&self.tanks, &self.lavs].iter() {
-            if group.labels.contains(&label.to_owned()) {
-                return Some(&group.class);
+        self.groups
+            .values()
+            .find(|group| group.labels.iter().any(|group_label| group_label == label))
+            .map(|group| group.class.as_str())
+    }
+
+    /// Errors if the same label is declared in more than one group, since an
+    /// ambiguous label-to-class mapping would otherwise silently resolve to
+    /// whichever group [`HashMap`] iteration happens to visit first.
+    fn validate(&self) -> Result<()> {
+        // Dedup within each group first so a repeated label inside a single
+        // group doesn't look like a cross-group collision below.
+        let all_labels: Vec<&String> = self
+            .groups
+            .values()
+            .flat_map(|g| g.labels.iter().unique())
+            .collect();
+        if has_duplicates(&all_labels).is_none() {
+            return Ok(());
+        }
+
+        let names: Vec<&String> = self.groups.keys().collect();
+        for (i, &name_a) in names.iter().enumerate() {
+            for &name_b in &names[i + 1..] {
+                let overlap =
+                    find_intersection(&self.groups[name_a].labels, &self.groups[name_b].labels);
+                if let Some(label) = overlap.first() {
+                    return Err(anyhow!(
+                        "label {label:?} is claimed by both \"{name_a}\" and \"{name_b}\""
+                    ));
+                }
             }
         }
-        None
+
+        unreachable!("has_duplicates found a collision but no pair of groups shares a label")
     }
 }
 
-fn export(data: &[ObjectDetection], classifier: &LabelClassification) -> Result<()> {
-    // Open the CSV file for writing
-    let mut writer = csv::Writer::from_path("tensorflow.csv")?;
+/// Train/val/test ratios for the stratified split. Must sum to `1.0`.
+#[derive(Debug, Clone, Copy)]
+struct SplitRatios {
+    train: f64,
+    val: f64,
+    test: f64,
+}
+
+const DEFAULT_SPLIT_RATIOS: SplitRatios = SplitRatios {
+    train: 0.8,
+    val: 0.1,
+    test: 0.1,
+};
+
+// Fixed so re-running `export` on the same data with the same seed always
+// reproduces the same split.
+const DEFAULT_SPLIT_SEED: u64 = 42;
+
+const SPLITS: [&str; 3] = ["train", "val", "test"];
+
+/// Parses `--split <train>/<val>/<test>` and `--seed <n>` from the command
+/// line, falling back to [`DEFAULT_SPLIT_RATIOS`] and [`DEFAULT_SPLIT_SEED`].
+fn parse_split_config(args: &[String]) -> Result<(SplitRatios, u64)> {
+    let mut ratios = DEFAULT_SPLIT_RATIOS;
+    let mut seed = DEFAULT_SPLIT_SEED;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--split" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| anyhow!("--split requires a train/val/test ratio argument"))?;
+                ratios = parse_split_ratios(value)?;
+            }
+            "--seed" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| anyhow!("--seed requires a numeric argument"))?;
+                seed = value
+                    .parse()
+                    .map_err(|_| anyhow!("--seed value must be a non-negative integer: {value}"))?;
+            }
+            _ => {}
+        }
+    }
+
+    Ok((ratios, seed))
+}
+
+fn parse_split_ratios(value: &str) -> Result<SplitRatios> {
+    let parts: Vec<&str> = value.split('/').collect();
+    let [train, val, test] = <[&str; 3]>::try_from(parts).map_err(|_| {
+        anyhow!("--split expects three slash-separated ratios, e.g. 0.8/0.1/0.1, got {value:?}")
+    })?;
+
+    let ratios = SplitRatios {
+        train: train.parse()?,
+        val: val.parse()?,
+        test: test.parse()?,
+    };
+
+    let total = ratios.train + ratios.val + ratios.test;
+    if (total - 1.0).abs() > 1e-6 {
+        return Err(anyhow!("--split ratios must sum to 1.0, got {total}"));
+    }
+
+    Ok(ratios)
+}
+
+/// Splits `data` into `train.csv`, `val.csv` and `test.csv`, stratified per
+/// resolved class: each class's rows are shuffled with a seeded RNG, then
+/// sliced by `ratios` so every class is proportionally represented in each
+/// output file.
+fn export(
+    data: &[ObjectDetection],
+    classifier: &LabelClassification,
+    ratios: SplitRatios,
+    seed: u64,
+) -> Result<()> {
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    // Bucket resolved rows by class, dropping anything the classifier doesn't map
+    let mut by_class: HashMap<&str, Vec<&ObjectDetection>> = HashMap::new();
+    for object in data {
+        match classifier.get_class(&object.class) {
+            Some(class) => by_class.entry(class).or_default().push(object),
+            None => println!("Skipping: {}", &object.class),
+        }
+    }
+
+    // split name -> (class, row) pairs
+    let mut splits: HashMap<&str, Vec<(&str, &ObjectDetection)>> =
+        SPLITS.iter().map(|&split| (split, Vec::new())).collect();
+
+    // Iterate classes in a stable order so the seeded RNG draws the same
+    // stream of randomness for the same class every run, regardless of the
+    // HashMap's (randomized) iteration order.
+    for (class, mut rows) in by_class.into_iter().sorted_by_key(|(class, _)| *class) {
+        rows.shuffle(&mut rng);
+        for (split, row) in SPLITS.into_iter().zip(split_rows(rows, ratios)) {
+            splits
+                .get_mut(split)
+                .unwrap()
+                .extend(row.into_iter().map(|object| (class, object)));
+        }
+    }
+
+    for split in SPLITS {
+        write_split_csv(split, &splits[split])?;
+    }
+
+    print_split_counts(&splits);
+
+    Ok(())
+}
+
+/// Slices a shuffled bucket into `(train, val, test)` chunks by `ratios`; any
+/// rounding remainder is folded into the test chunk.
+fn split_rows<T>(rows: Vec<T>, ratios: SplitRatios) -> [Vec<T>; 3] {
+    let total = rows.len();
+    let train_n = (total as f64 * ratios.train) as usize;
+    let val_n = (total as f64 * ratios.val) as usize;
+
+    let mut rows = rows.into_iter();
+    let train: Vec<T> = (&mut rows).take(train_n).collect();
+    let val: Vec<T> = (&mut rows).take(val_n).collect();
+    let test: Vec<T> = rows.collect();
+
+    [train, val, test]
+}
+
+fn write_split_csv(split: &str, rows: &[(&str, &ObjectDetection)]) -> Result<()> {
+    let mut writer = csv::Writer::from_path(format!("{split}.csv"))?;
 
-    // Write the header row
     writer.write_record([
         "filename", "width", "height", "class", "xmin", "ymin", "xmax", "ymax",
     ])?;
 
-    // Write the data rows
-    for object in data {
+    for (class, object) in rows {
         let base_dir = get_base_dir(&object.filename);
         let filename = get_relative_filename(&object.filename, base_dir);
         let (xmin, ymin, xmax, ymax) = calculate_bounding_box::<80>(object.width, object.height);
 
-        if let Some(class) = classifier.get_class(&object.class) {
-            writer.write_record([
-                &filename.to_string_lossy().to_string(),
-                &object.width.to_string(),
-                &object.height.to_string(),
-                &class.to_owned(),
-                &xmin.to_string(),
-                &ymin.to_string(),
-                &xmax.to_string(),
-                &ymax.to_string(),
-            ])?;
-        } else {
-            println!("Skipping: {}", &object.class)
-        }
+        writer.write_record([
+            &filename.to_string_lossy().to_string(),
+            &object.width.to_string(),
+            &object.height.to_string(),
+            &class.to_string(),
+            &xmin.to_string(),
+            &ymin.to_string(),
+            &xmax.to_string(),
+            &ymax.to_string(),
+        ])?;
     }
 
     Ok(())
 }
 
+fn print_split_counts(splits: &HashMap<&str, Vec<(&str, &ObjectDetection)>>) {
+    for split in SPLITS {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for (class, _) in &splits[split] {
+            *counts.entry(class).or_default() += 1;
+        }
+        println!("{split}.csv: {} row(s)", splits[split].len());
+        for (class, count) in counts.iter().sorted() {
+            println!("  {class}: {count}");
+        }
+    }
+}
+
 fn calculate_bounding_box<const PERCENT: u32>(width: u32, height: u32) -> (u32, u32, u32, u32) {
     let x_center = width / 2;
     let y_center = height / 2;
@@ -210,7 +644,14 @@ fn calculate_bounding_box<const PERCENT: u32>(width: u32, height: u32) -> (u32,
     (xmin, ymin, xmax, ymax)
 }
 
+/// Reads `(width, height)` without decoding pixels: JPEG SOF markers, PNG
+/// IHDR and BMP headers are parsed directly, falling back to a full decode
+/// via the `image` crate for anything else or a malformed header.
 fn image_size<P: AsRef<Path>>(path: P) -> Result<(u32, u32), image::ImageError> {
+    if let Some(dimensions) = read_header_dimensions(path.as_ref()) {
+        return Ok(dimensions);
+    }
+
     // Open the image file and decode it using the image crate
     let img = image::open(path)?;
 
@@ -218,6 +659,104 @@ fn image_size<P: AsRef<Path>>(path: P) -> Result<(u32, u32), image::ImageError>
     Ok(img.dimensions())
 }
 
+fn read_header_dimensions(path: &Path) -> Option<(u32, u32)> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    let mut file = fs::File::open(path).ok()?;
+
+    match ext.as_str() {
+        "png" => read_png_dimensions(&mut file),
+        "bmp" => read_bmp_dimensions(&mut file),
+        "jpg" | "jpeg" => read_jpeg_dimensions(&mut file),
+        _ => None,
+    }
+}
+
+fn read_png_dimensions(file: &mut fs::File) -> Option<(u32, u32)> {
+    // Signature (8) + IHDR length (4) + "IHDR" (4) + width (4) + height (4)
+    let mut buf = [0u8; 24];
+    file.read_exact(&mut buf).ok()?;
+
+    if &buf[0..8] != b"\x89PNG\r\n\x1a\n" || &buf[12..16] != b"IHDR" {
+        return None;
+    }
+
+    let width = u32::from_be_bytes(buf[16..20].try_into().ok()?);
+    let height = u32::from_be_bytes(buf[20..24].try_into().ok()?);
+    Some((width, height))
+}
+
+fn read_bmp_dimensions(file: &mut fs::File) -> Option<(u32, u32)> {
+    // File header (14) + biSize (4) + biWidth (4) + biHeight (4) from the DIB header
+    let mut buf = [0u8; 26];
+    file.read_exact(&mut buf).ok()?;
+
+    if &buf[0..2] != b"BM" {
+        return None;
+    }
+
+    let width = i32::from_le_bytes(buf[18..22].try_into().ok()?);
+    // A negative height means the bitmap is stored top-down rather than bottom-up.
+    let height = i32::from_le_bytes(buf[22..26].try_into().ok()?);
+    Some((width.unsigned_abs(), height.unsigned_abs()))
+}
+
+fn read_jpeg_dimensions(file: &mut fs::File) -> Option<(u32, u32)> {
+    const SOF_MARKERS: [u8; 13] = [
+        0xC0, 0xC1, 0xC2, 0xC3, 0xC5, 0xC6, 0xC7, 0xC9, 0xCA, 0xCB, 0xCD, 0xCE, 0xCF,
+    ];
+    const MARKERS_WITHOUT_PAYLOAD: [u8; 10] =
+        [0x01, 0xD0, 0xD1, 0xD2, 0xD3, 0xD4, 0xD5, 0xD6, 0xD7, 0xD8];
+
+    let mut magic = [0u8; 2];
+    file.read_exact(&mut magic).ok()?;
+    if magic != [0xFF, 0xD8] {
+        return None;
+    }
+
+    loop {
+        // Markers are introduced by a 0xFF byte, possibly preceded by fill bytes
+        let mut byte = [0u8; 1];
+        loop {
+            file.read_exact(&mut byte).ok()?;
+            if byte[0] == 0xFF {
+                break;
+            }
+        }
+        let mut marker = [0u8; 1];
+        loop {
+            file.read_exact(&mut marker).ok()?;
+            if marker[0] != 0xFF {
+                break;
+            }
+        }
+        let marker = marker[0];
+
+        if marker == 0xD9 || marker == 0xDA {
+            // End of image / start of scan: no SOF marker found before the
+            // compressed data, give up and let the caller fall back to a
+            // full decode
+            return None;
+        }
+        if MARKERS_WITHOUT_PAYLOAD.contains(&marker) {
+            continue;
+        }
+
+        let mut length = [0u8; 2];
+        file.read_exact(&mut length).ok()?;
+        let length = u16::from_be_bytes(length);
+
+        if SOF_MARKERS.contains(&marker) {
+            let mut data = [0u8; 5];
+            file.read_exact(&mut data).ok()?;
+            let height = u16::from_be_bytes([data[1], data[2]]) as u32;
+            let width = u16::from_be_bytes([data[3], data[4]]) as u32;
+            return Some((width, height));
+        }
+
+        file.seek(SeekFrom::Current(i64::from(length) - 2)).ok()?;
+    }
+}
+
 fn find_intersection<T: PartialEq + Clone>(vec1: &[T], vec2: &[T]) -> Vec<T> {
     vec1.iter().filter(|&n| vec2.contains(n)).cloned().collect()
 }